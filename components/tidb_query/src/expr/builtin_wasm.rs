@@ -1,5 +1,7 @@
 use super::{EvalContext, Result, ScalarFunc};
 use crate::codec::Datum;
+use udf::wasm::{WasmArg, WasmValue};
+use udf::WasmEngine as _;
 
 impl ScalarFunc {
     pub fn nbody<'a, 'b: 'a>(
@@ -9,15 +11,91 @@ impl ScalarFunc {
     ) -> Result<Option<f64>> {
         let input = try_opt!(self.children[0].eval_int(ctx, row));
         if let Some(wasm) = ctx.wasm_store.get(self.wasm_udf_id)? {
-            let res = wasm.execute("udf_main", vec![input.to_string()])?;
-            if let Some(v) = res.as_ref()[0].f64() {
+            let res = wasm.execute_with_limits(
+                "udf_main",
+                vec![input.to_string()],
+                udf::wasm::DEFAULT_FUEL,
+                Some(std::time::Duration::from_secs(5)),
+            )?;
+            // A guest that called `proc_exit` instead of returning normally
+            // leaves `returns` empty; there's no value to read in that case.
+            if let Some(v) = res.output.returns.get(0).and_then(|v| v.f64()) {
                 return Ok(Some(v));
             }
         }
         Ok(None)
     }
 
-    // pub fn wasm_call(&self, ctx: &mut EvalContext, row: &[Datum]) -> Result<Option<()>> {
-    //     Ok(None)
-    // }
+    /// Generic dispatch for any UDF registered against a Wasm module,
+    /// alongside the one-off `nbody` wiring above. Each child expression is
+    /// evaluated to a `Datum` and marshaled into the shape the guest
+    /// expects: numeric `Datum`s pass straight through as Wasm scalars,
+    /// while `VARCHAR`/`BLOB`/`DECIMAL` values are copied into guest memory
+    /// via `udf_alloc` and passed as `(ptr, len)`. The guest's return value
+    /// is decoded back into a `Datum` the same way.
+    pub fn wasm_call<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<Datum>> {
+        let wasm = match ctx.wasm_store.get(self.wasm_udf_id)? {
+            Some(wasm) => wasm,
+            None => return Ok(None),
+        };
+        let mut args = Vec::with_capacity(self.children.len());
+        for child in &self.children {
+            let datum = child.eval(ctx, row)?;
+            args.push(datum_to_wasm_arg(&datum)?);
+        }
+        let (output, _points_remaining) = wasm.call_with_limits(
+            "udf_main",
+            args,
+            udf::wasm::DEFAULT_FUEL,
+            Some(std::time::Duration::from_secs(5)),
+        )?;
+        // A UDF that called `proc_exit` instead of returning normally has
+        // no value to decode.
+        Ok(output.value.and_then(wasm_value_to_datum))
+    }
+}
+
+/// Map a `Datum` onto the Wasm-level argument it marshals to. Integers and
+/// floats pass through as Wasm scalars; everything else (strings, blobs,
+/// decimals) is passed as bytes and marshaled through guest memory by
+/// `WASM::call_with_limits`.
+fn datum_to_wasm_arg(datum: &Datum) -> Result<WasmArg> {
+    Ok(match datum {
+        Datum::I64(v) => WasmArg::I64(*v),
+        Datum::U64(v) => WasmArg::I64(*v as i64),
+        Datum::F64(v) => WasmArg::F64(*v),
+        Datum::Bytes(bytes) => WasmArg::Bytes(bytes.clone()),
+        Datum::Dec(dec) => WasmArg::Bytes(dec.to_string().into_bytes()),
+        Datum::Time(time) => WasmArg::Bytes(time.to_string().into_bytes()),
+        Datum::Dur(dur) => WasmArg::Bytes(dur.to_string().into_bytes()),
+        Datum::Json(json) => WasmArg::Bytes(json.to_string().into_bytes()),
+        Datum::Null | Datum::Min | Datum::Max => WasmArg::Bytes(Vec::new()),
+    })
+}
+
+/// Inverse of [`datum_to_wasm_arg`] for return values: a Wasm scalar becomes
+/// the matching numeric `Datum`, and a decoded byte buffer becomes
+/// `Datum::Bytes` (the caller's registered SQL return type decides how it
+/// gets further parsed, e.g. into a VARCHAR or DECIMAL).
+fn wasm_value_to_datum(value: WasmValue) -> Option<Datum> {
+    match value {
+        WasmValue::Scalar(val) => {
+            if let Some(v) = val.i64() {
+                Some(Datum::I64(v))
+            } else if let Some(v) = val.f64() {
+                Some(Datum::F64(v))
+            } else if let Some(v) = val.i32() {
+                Some(Datum::I64(v as i64))
+            } else if let Some(v) = val.f32() {
+                Some(Datum::F64(v as f64))
+            } else {
+                None
+            }
+        }
+        WasmValue::Bytes(bytes) => Some(Datum::Bytes(bytes)),
+    }
 }
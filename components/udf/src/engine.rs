@@ -0,0 +1,121 @@
+//! Backend abstraction over how a compiled UDF module actually runs.
+//!
+//! TiKV nodes executing the same UDF must agree on its result, but the
+//! `wasmer` Cranelift JIT can behave subtly differently across platforms (or
+//! be undesirable to run at all in a locked-down environment). [`WasmEngine`]
+//! gives `ScalarFunc` one interface over two backends: the existing
+//! `wasmer`-based JIT for throughput, and a [`wasmi`](wasmi_engine)
+//! bytecode interpreter for deterministic, sandbox-friendly execution on
+//! replication-sensitive paths. Both take the same fuel/deadline parameters.
+//! The `wasmi` backend implements only `execute_with_limits`: its
+//! `call_with_limits` always returns an error, and its deadline is checked
+//! after the call returns rather than interrupting it.
+use crate::wasm::{TypedCallOutput, WasmArg, WASM};
+use crate::Result;
+use std::time::Duration;
+
+pub mod wasmi_engine;
+
+pub use wasmi_engine::WasmiModule;
+
+/// Which backend executes a compiled UDF module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// The existing `wasmer` Cranelift JIT. Fast, but codegen can vary
+    /// across host platforms.
+    WasmerJit,
+    /// A pure interpreter (`wasmi`). Slower, but its execution is
+    /// deterministic and doesn't depend on a JIT being available or
+    /// trustworthy in the deployment environment.
+    WasmiInterpreter,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::WasmerJit
+    }
+}
+
+/// The surface every backend exposes, so `ScalarFunc` dispatch is written
+/// once against `dyn WasmEngine`/[`CompiledUdf`] rather than against a
+/// specific backend's types.
+pub trait WasmEngine {
+    /// Run `endpoint` with string-encoded numeric arguments, matching
+    /// [`crate::wasm::WASM::execute_with_limits`]'s contract.
+    fn execute_with_limits(
+        &self,
+        endpoint: &str,
+        args: Vec<String>,
+        fuel: u64,
+        deadline: Option<Duration>,
+    ) -> Result<crate::wasm::MeteredResult>;
+
+    /// Run `endpoint` with marshaled arguments, matching
+    /// [`crate::wasm::WASM::call_with_limits`]'s contract.
+    fn call_with_limits(
+        &self,
+        endpoint: &str,
+        args: Vec<WasmArg>,
+        fuel: u64,
+        deadline: Option<Duration>,
+    ) -> Result<(TypedCallOutput, u64)>;
+}
+
+impl WasmEngine for WASM {
+    fn execute_with_limits(
+        &self,
+        endpoint: &str,
+        args: Vec<String>,
+        fuel: u64,
+        deadline: Option<Duration>,
+    ) -> Result<crate::wasm::MeteredResult> {
+        WASM::execute_with_limits(self, endpoint, args, fuel, deadline)
+    }
+
+    fn call_with_limits(
+        &self,
+        endpoint: &str,
+        args: Vec<WasmArg>,
+        fuel: u64,
+        deadline: Option<Duration>,
+    ) -> Result<(TypedCallOutput, u64)> {
+        WASM::call_with_limits(self, endpoint, args, fuel, deadline)
+    }
+}
+
+/// A compiled UDF module, tagged with the backend it should run on. This is
+/// what `Store::get` hands back, so callers dispatch through [`WasmEngine`]
+/// without caring which backend a given UDF was configured for.
+#[derive(Clone)]
+pub enum CompiledUdf {
+    Wasmer(WASM),
+    Wasmi(WasmiModule),
+}
+
+impl WasmEngine for CompiledUdf {
+    fn execute_with_limits(
+        &self,
+        endpoint: &str,
+        args: Vec<String>,
+        fuel: u64,
+        deadline: Option<Duration>,
+    ) -> Result<crate::wasm::MeteredResult> {
+        match self {
+            CompiledUdf::Wasmer(wasm) => wasm.execute_with_limits(endpoint, args, fuel, deadline),
+            CompiledUdf::Wasmi(wasmi) => wasmi.execute_with_limits(endpoint, args, fuel, deadline),
+        }
+    }
+
+    fn call_with_limits(
+        &self,
+        endpoint: &str,
+        args: Vec<WasmArg>,
+        fuel: u64,
+        deadline: Option<Duration>,
+    ) -> Result<(TypedCallOutput, u64)> {
+        match self {
+            CompiledUdf::Wasmer(wasm) => wasm.call_with_limits(endpoint, args, fuel, deadline),
+            CompiledUdf::Wasmi(wasmi) => wasmi.call_with_limits(endpoint, args, fuel, deadline),
+        }
+    }
+}
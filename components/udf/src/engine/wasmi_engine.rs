@@ -0,0 +1,233 @@
+//! The `wasmi`-backed interpreter implementation of [`super::WasmEngine`].
+//!
+//! Unlike the `wasmer` JIT path in `wasm.rs`, this never generates machine
+//! code: every call walks the bytecode in `wasmi`'s interpreter loop, which
+//! is what makes its results deterministic across hosts. It's meant for
+//! replication-sensitive UDF calls where "slower but every replica agrees"
+//! beats "fast but platform-dependent".
+use crate::wasm::{ExecutionError, ExecutionOutput, MeteredResult, TypedCallOutput, WasmArg};
+use crate::Result;
+use anyhow::{anyhow, bail};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use wasmi::{Config, Engine, Instance, Linker, Module, Store, Val};
+
+/// A UDF module compiled for the `wasmi` interpreter. Cheap to clone: the
+/// compiled `Module` and `Engine` are both reference-counted internally.
+#[derive(Clone)]
+pub struct WasmiModule {
+    module: Module,
+    engine: Engine,
+}
+
+impl WasmiModule {
+    pub fn compile(contents: &[u8]) -> Result<Self> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, contents)?;
+        Ok(Self { module, engine })
+    }
+
+    fn instantiate(&self, fuel: u64) -> Result<(Store<()>, Instance)> {
+        let mut store = Store::new(&self.engine, ());
+        store
+            .set_fuel(fuel)
+            .map_err(|e| anyhow!("failed to set fuel budget: {}", e))?;
+        let linker = Linker::new(&self.engine);
+        let instance = linker
+            .instantiate(&mut store, &self.module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|e| anyhow!("failed to instantiate module: {}", e))?;
+        Ok((store, instance))
+    }
+
+    fn run_with_deadline<F, T>(
+        &self,
+        mut store: Store<()>,
+        fuel: u64,
+        deadline: Option<Duration>,
+        call: F,
+    ) -> Result<(T, u64)>
+    where
+        F: FnOnce(&mut Store<()>) -> Result<T>,
+    {
+        // `wasmi`'s interpreter doesn't give us a handle to poke from another
+        // thread mid-call the way `wasmer`'s metering globals do, so the
+        // watchdog here cannot interrupt the call: `call` runs on this
+        // thread until it returns or runs out of fuel, and only then, if the
+        // deadline passed in the meantime, is its result discarded and
+        // `DeadlineExceeded` reported.
+        let cancelled = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let watchdog = deadline.map(|deadline| {
+            let cancelled = Arc::clone(&cancelled);
+            let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+            let handle = thread::spawn(move || {
+                if done_rx.recv_timeout(deadline).is_err() {
+                    cancelled.store(true, std::sync::atomic::Ordering::SeqCst);
+                }
+            });
+            (handle, done_tx)
+        });
+
+        let result = call(&mut store);
+
+        if let Some((handle, done_tx)) = watchdog {
+            let _ = done_tx.send(());
+            let _ = handle.join();
+        }
+
+        let fuel_remaining = store.get_fuel().unwrap_or(0);
+
+        if cancelled.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(ExecutionError::DeadlineExceeded.into());
+        }
+        match result {
+            Ok(value) => Ok((value, fuel_remaining)),
+            Err(_) if fuel_remaining == 0 && fuel > 0 => Err(ExecutionError::FuelExhausted.into()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn execute_with_limits(
+        &self,
+        endpoint: &str,
+        args: Vec<String>,
+        fuel: u64,
+        deadline: Option<Duration>,
+    ) -> Result<MeteredResult> {
+        let (mut store, instance) = self.instantiate(fuel)?;
+        let func = instance
+            .get_func(&store, endpoint)
+            .ok_or_else(|| anyhow!("No export `{}` found in the module", endpoint))?;
+        let func_ty = func.ty(&store);
+        if func_ty.params().len() != args.len() {
+            bail!(
+                "Function expected {} arguments, but received {}: \"{}\"",
+                func_ty.params().len(),
+                args.len(),
+                args.join(" ")
+            );
+        }
+        let invoke_args = args
+            .iter()
+            .zip(func_ty.params().iter())
+            .map(|(arg, ty)| parse_scalar(arg, ty))
+            .collect::<Result<Vec<_>>>()?;
+        let (returns, points_remaining) =
+            self.run_with_deadline(store, fuel, deadline, move |store| {
+                let mut results = vec![Val::I32(0); func_ty.results().len()];
+                func.call(store, &invoke_args, &mut results)
+                    .map_err(|e| anyhow!("{}", e))?;
+                Ok(results.into_boxed_slice())
+            })?;
+        Ok(MeteredResult {
+            output: ExecutionOutput {
+                returns: to_wasmer_vals(&returns),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+                exit_code: None,
+            },
+            points_remaining,
+        })
+    }
+
+    pub fn call_with_limits(
+        &self,
+        _endpoint: &str,
+        args: Vec<WasmArg>,
+        _fuel: u64,
+        _deadline: Option<Duration>,
+    ) -> Result<(TypedCallOutput, u64)> {
+        // This backend returns an error for every `call_with_limits` call,
+        // numeric or not; byte-marshaled (string/blob/decimal) arguments get
+        // their own error message. Numeric UDFs run on the interpreter only
+        // through `execute_with_limits`.
+        if args.iter().any(|arg| matches!(arg, WasmArg::Bytes(_))) {
+            bail!("the wasmi backend does not yet support byte-marshaled UDF arguments");
+        }
+        bail!("the wasmi backend does not yet implement call_with_limits");
+    }
+}
+
+fn parse_scalar(arg: &str, ty: &wasmi::core::ValType) -> Result<Val> {
+    use wasmi::core::ValType;
+    Ok(match ty {
+        ValType::I32 => Val::I32(
+            arg.parse()
+                .map_err(|_| anyhow!("Can't convert `{}` into a i32", arg))?,
+        ),
+        ValType::I64 => Val::I64(
+            arg.parse()
+                .map_err(|_| anyhow!("Can't convert `{}` into a i64", arg))?,
+        ),
+        ValType::F32 => Val::F32(
+            arg.parse::<f32>()
+                .map_err(|_| anyhow!("Can't convert `{}` into a f32", arg))?
+                .into(),
+        ),
+        ValType::F64 => Val::F64(
+            arg.parse::<f64>()
+                .map_err(|_| anyhow!("Can't convert `{}` into a f64", arg))?
+                .into(),
+        ),
+        other => bail!("Don't know how to convert {} into {:?}", arg, other),
+    })
+}
+
+/// Translate `wasmi::Val`s into the `wasmer::Val`s the rest of the crate
+/// (and `ScalarFunc`) already knows how to read, so callers don't need a
+/// second result type depending on which backend ran the call.
+fn to_wasmer_vals(values: &[Val]) -> Box<[wasmer::Val]> {
+    values
+        .iter()
+        .map(|v| match v {
+            Val::I32(v) => wasmer::Val::I32(*v),
+            Val::I64(v) => wasmer::Val::I64(*v),
+            Val::F32(v) => wasmer::Val::F32(f32::from(*v)),
+            Val::F64(v) => wasmer::Val::F64(f64::from(*v)),
+            _ => wasmer::Val::I32(0),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmi::core::ValType;
+
+    #[test]
+    fn test_parse_scalar_converts_each_numeric_type() {
+        assert_eq!(parse_scalar("42", &ValType::I32).unwrap(), Val::I32(42));
+        assert_eq!(parse_scalar("-7", &ValType::I64).unwrap(), Val::I64(-7));
+        assert_eq!(
+            parse_scalar("1.5", &ValType::F32).unwrap(),
+            Val::F32(1.5f32.into())
+        );
+        assert_eq!(
+            parse_scalar("2.5", &ValType::F64).unwrap(),
+            Val::F64(2.5f64.into())
+        );
+    }
+
+    #[test]
+    fn test_parse_scalar_rejects_unparseable_input() {
+        assert!(parse_scalar("not a number", &ValType::I32).is_err());
+    }
+
+    #[test]
+    fn test_to_wasmer_vals_round_trips_numeric_values() {
+        let values = [Val::I32(1), Val::I64(2), Val::F32(3.0.into()), Val::F64(4.0.into())];
+        let converted = to_wasmer_vals(&values);
+        assert_eq!(
+            converted.as_ref(),
+            &[
+                wasmer::Val::I32(1),
+                wasmer::Val::I64(2),
+                wasmer::Val::F32(3.0),
+                wasmer::Val::F64(4.0),
+            ]
+        );
+    }
+}
@@ -1,6 +1,10 @@
+pub mod engine;
+pub mod host;
 pub mod store;
 pub mod wasm;
+pub use engine::{Backend, CompiledUdf, WasmEngine};
 pub use store::Store;
+pub use wasm::{ExecutionError, PreopenedDir, WasiPolicy};
 pub type Result<T> = anyhow::Result<T>;
 pub use anyhow::Error as WasmError;
 pub use wasmer::Val;
@@ -1,20 +1,55 @@
-use crate::wasm::WASM;
+use crate::engine::{Backend, CompiledUdf, WasmiModule};
+use crate::wasm::{self, WasiPolicy, WASM};
 use crate::Result;
 use serde_json::{Map, Value};
 use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 use std::{collections::HashMap, fs};
+use wasmer::Module;
 
 const STORE_PATH: &'static str = ".wasm_store";
 
 fn wasm_path(id: u64) -> PathBuf {
     Path::new(STORE_PATH).join(format!("{}.wasm", id))
 }
+
+/// Path of the precompiled artifact for module `id`, sitting next to its
+/// `.wasm` source so a process restart can skip recompilation.
+fn module_cache_path(id: u64) -> PathBuf {
+    Path::new(STORE_PATH).join(format!("{}.module", id))
+}
+
 /// Store all the wasm modules
-#[derive(Default, Debug)]
 pub struct Store {
-    cache: HashMap<u64, WASM>,
+    cache: HashMap<u64, CompiledUdf>,
+    /// Compiled `Module`s keyed by UDF id, so `ScalarFunc` dispatch pays the
+    /// `Module::new` compilation cost once per id rather than once per row.
+    /// Only populated for the `wasmer` backend; `wasmi` compiles directly
+    /// into the `CompiledUdf` cached below.
+    modules: HashMap<u64, Module>,
+    engine: wasmer::Store,
+    /// Which backend newly-loaded UDFs run on. Existing entries in `cache`
+    /// keep whatever backend they were loaded with; changing this only
+    /// affects ids not yet seen by `get`.
+    backend: Backend,
+    /// WASI capabilities granted to newly-loaded `wasmer` UDFs (env vars,
+    /// preopened directories, clock/random access). Like `backend`, this
+    /// only affects ids not yet seen by `get`; it has no effect on the
+    /// `wasmi` backend, which doesn't run WASI modules.
+    wasi_policy: WasiPolicy,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self {
+            cache: HashMap::new(),
+            modules: HashMap::new(),
+            engine: wasm::metered_store(wasm::DEFAULT_FUEL),
+            backend: Backend::default(),
+            wasi_policy: WasiPolicy::deny_all(),
+        }
+    }
 }
 
 impl Store {
@@ -24,6 +59,47 @@ impl Store {
         Ok(Store::default())
     }
 
+    /// Init a wasm store that runs newly-loaded UDFs on `backend`, e.g. the
+    /// deterministic `wasmi` interpreter for replication-sensitive paths.
+    pub fn init_with_backend(backend: Backend) -> Result<Self> {
+        let mut store = Store::init()?;
+        store.backend = backend;
+        Ok(store)
+    }
+
+    /// Init a wasm store that grants newly-loaded `wasmer` UDFs the
+    /// capabilities in `wasi_policy` instead of the fully-locked-down
+    /// default, e.g. to allow a trusted UDF to read a preopened directory.
+    pub fn init_with_wasi_policy(wasi_policy: WasiPolicy) -> Result<Self> {
+        let mut store = Store::init()?;
+        store.wasi_policy = wasi_policy;
+        Ok(store)
+    }
+
+    /// Get the compiled module for `id`, compiling (and persisting a
+    /// precompiled artifact) on first use and serving cached `Module`s
+    /// afterward. `Module` is cheap to clone (it's `Arc`-backed), so callers
+    /// get shared compiled code without re-running the compiler.
+    pub fn get_module(&mut self, id: u64) -> Result<Module> {
+        if let Some(module) = self.modules.get(&id) {
+            return Ok(module.clone());
+        }
+        let cache_path = module_cache_path(id);
+        let module = if cache_path.exists() {
+            let serialized = fs::read(&cache_path)?;
+            unsafe { Module::deserialize(&self.engine, &serialized)? }
+        } else {
+            let contents = fs::read(wasm_path(id))?;
+            let module = Module::new(&self.engine, &contents)?;
+            if let Ok(serialized) = module.serialize() {
+                let _ = fs::write(&cache_path, serialized);
+            }
+            module
+        };
+        self.modules.insert(id, module.clone());
+        Ok(module)
+    }
+
     /// Store the wasm payload
     // pub fn insert(&mut self, name: &str, payload: Vec<u8>) -> Result<()> {
     //     let wasm_file = Path::new(STORE_PATH).join(name);
@@ -33,13 +109,26 @@ impl Store {
     //     self.flush()
     // }
 
-    /// Get wasm content by name
-    pub fn get(&mut self, id: u64) -> Result<Option<WASM>> {
+    /// Get wasm content by id, compiled for this store's configured
+    /// `Backend`.
+    pub fn get(&mut self, id: u64) -> Result<Option<CompiledUdf>> {
         if !self.cache.contains_key(&id) {
-            let contents = fs::read(wasm_path(id))?;
-            let wasm = WASM::new("udf_main".to_owned(), contents);
-            self.cache.insert(id, wasm.clone());
-            Ok(Some(wasm))
+            let compiled = match self.backend {
+                Backend::WasmerJit => {
+                    let contents = fs::read(wasm_path(id))?;
+                    let module = self.get_module(id)?;
+                    CompiledUdf::Wasmer(
+                        WASM::with_module("udf_main".to_owned(), contents, module)
+                            .with_wasi_policy(self.wasi_policy.clone()),
+                    )
+                }
+                Backend::WasmiInterpreter => {
+                    let contents = fs::read(wasm_path(id))?;
+                    CompiledUdf::Wasmi(WasmiModule::compile(&contents)?)
+                }
+            };
+            self.cache.insert(id, compiled.clone());
+            Ok(Some(compiled))
         } else {
             Ok(self.cache.get(&id).cloned())
         }
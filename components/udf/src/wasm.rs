@@ -1,38 +1,580 @@
 use crate::Result;
 use anyhow::{anyhow, bail};
 use std::collections::HashMap;
+use std::fmt;
 use std::str;
-use wasmer::{imports, ExportError, Function, Instance, Module, Store, Val, ValType};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use wasmer::{
+    CompilerConfig, ExportError, Function, Instance, Module, Store, Val, ValType,
+};
+use wasmer_compiler_cranelift::Cranelift;
+use wasmer_engine_jit::JIT;
+use wasmer_middlewares::metering::{self, MeteringPoints};
+use wasmer_middlewares::Metering;
+use std::io::Read as _;
 use wasmer_runtime::{func, imports as runtime_imports, instantiate, Ctx, Value};
-use wasmer_wasi::{get_wasi_version, WasiError, WasiState};
+use wasmer_wasi::{get_wasi_version, Pipe, WasiEnv, WasiError, WasiState};
+
+/// How many warm, not-yet-touched instances [`InstancePool`] keeps on hand
+/// per module. Small on purpose: the expensive part is compilation (already
+/// shared via the cached `Module`), not instantiation.
+const POOL_CAPACITY: usize = 4;
+
+/// A small reservoir of pre-instantiated `Instance`s sharing one compiled
+/// `Module`, so the hot path for a UDF call is "pop a warm instance with its
+/// own fresh linear memory" instead of "instantiate from scratch". Instances
+/// are single-use: once handed out by `WASM::acquire_pooled`, they carry a
+/// dirtied memory and are dropped rather than returned; the pool tops itself
+/// back up from a background thread (see `WASM::spawn_refill`) instead of on
+/// the calling thread, so an `acquire_pooled` call that empties the pool doesn't make that call pay
+/// for the next caller's instantiation, and doesn't hold `pool`'s mutex
+/// across it either.
+#[derive(Default)]
+pub(crate) struct InstancePool {
+    idle: Vec<Instance>,
+    /// Set while a background refill thread is already topping this pool
+    /// back up, so concurrent callers that each drain the last idle
+    /// instance don't each spawn their own refill thread.
+    refilling: bool,
+}
+
+impl InstancePool {
+    /// Take a warm instance if one's on hand. Creating a new one when the
+    /// pool is empty is deliberately not this type's job: it needs a
+    /// freshly-built `ImportObject` each time (see `WASM::acquire_pooled`),
+    /// which only the caller knows how to build.
+    fn pop_idle(&mut self) -> Option<Instance> {
+        self.idle.pop()
+    }
+}
+
+/// Errors specific to metered/deadline-bound execution, distinguished from
+/// ordinary guest traps so `ScalarFunc` can tell "the UDF misbehaved" apart
+/// from "the UDF was killed for running too long".
+#[derive(Debug)]
+pub enum ExecutionError {
+    /// The module consumed its entire fuel budget before returning.
+    FuelExhausted,
+    /// The wall-clock deadline elapsed before the call returned.
+    DeadlineExceeded,
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionError::FuelExhausted => write!(f, "UDF ran out of fuel"),
+            ExecutionError::DeadlineExceeded => write!(f, "UDF exceeded its execution deadline"),
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+/// Everything a Wasm call can hand back: the function's return values, plus
+/// whatever the guest wrote to stdout/stderr and the code it asked to exit
+/// with. Captured instead of forwarded straight to the host process, since a
+/// UDF calling `proc_exit` must not be able to take the server down with it.
+#[derive(Debug, Default)]
+pub struct ExecutionOutput {
+    pub returns: Box<[Val]>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub exit_code: Option<i32>,
+}
+
+/// Outcome of a fuel-metered call: the execution output plus the fuel that
+/// was left over, so callers can account for how expensive a UDF invocation
+/// was.
+#[derive(Debug)]
+pub struct MeteredResult {
+    pub output: ExecutionOutput,
+    pub points_remaining: u64,
+}
+
+/// Every Wasm operator costs one point. Keeping this flat (rather than
+/// weighting by opcode) is enough to bound runaway loops without having to
+/// maintain a cost table per instruction.
+fn cost_function(_operator: &wasmer::wasmparser::Operator) -> u64 {
+    1
+}
+
+/// Fuel budget used when a caller doesn't have a more specific limit in
+/// mind, e.g. a scalar UDF evaluated per-row during a query.
+pub const DEFAULT_FUEL: u64 = 10_000_000;
+
+/// A single Wasm-level call argument after marshaling. Plain scalars pass
+/// through unchanged; `Bytes` (a `VARCHAR`/`BLOB`/`DECIMAL`-as-string, say)
+/// is copied into guest memory through the module's exported `udf_alloc`
+/// allocator and expands to a `(ptr, len)` pair of Wasm params.
+#[derive(Debug, Clone)]
+pub enum WasmArg {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bytes(Vec<u8>),
+}
+
+/// Decoded return value of a [`WASM::call_with_limits`] invocation that
+/// returned normally (as opposed to exiting — see [`TypedCallOutput`]).
+#[derive(Debug)]
+pub enum WasmValue {
+    Scalar(Val),
+    Bytes(Vec<u8>),
+}
+
+/// Outcome of a [`WASM::call_with_limits`] invocation, mirroring what
+/// [`ExecutionOutput`] captures for [`WASM::execute_with_limits`]: a guest
+/// calling `proc_exit` is reported here rather than as an `Err`, and
+/// whatever it wrote to stdout/stderr is captured rather than discarded.
+#[derive(Debug, Default)]
+pub struct TypedCallOutput {
+    /// `None` when the guest exited via `proc_exit` instead of returning.
+    pub value: Option<WasmValue>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub exit_code: Option<i32>,
+}
+
+/// Validate a guest-controlled `(ptr, len)` pair before it's used to slice
+/// linear memory. Both come from the guest (an allocator's return value, or
+/// a function's own return values), so a buggy or adversarial module can
+/// hand back anything: `ptr + len` can overflow a `u32`, and even when it
+/// doesn't, the range can run past the end of memory. Either slices a
+/// `Vec`/`Cell` view out of bounds, which panics the host thread rather than
+/// failing the call.
+pub(crate) fn checked_range(ptr: u32, len: u32, memory_size: u64) -> Result<std::ops::Range<usize>> {
+    let end = (ptr as u64)
+        .checked_add(len as u64)
+        .filter(|&end| end <= memory_size)
+        .ok_or_else(|| {
+            anyhow!(
+                "guest referenced an out-of-bounds memory region (ptr={}, len={}, memory size={})",
+                ptr,
+                len,
+                memory_size
+            )
+        })?;
+    Ok(ptr as usize..end as usize)
+}
+
+/// Copy `bytes` into a buffer obtained from the module's exported
+/// `udf_alloc(len: i32) -> ptr: i32`, returning where it landed.
+fn alloc_and_write(instance: &Instance, bytes: &[u8]) -> Result<(u32, u32)> {
+    let alloc = instance
+        .exports
+        .get_function("udf_alloc")
+        .map_err(|_| anyhow!("module does not export `udf_alloc`"))?;
+    let ptr = alloc
+        .call(&[Val::I32(bytes.len() as i32)])?
+        .get(0)
+        .and_then(|v| v.i32())
+        .ok_or_else(|| anyhow!("`udf_alloc` did not return a pointer"))?;
+    let memory = instance.exports.get_memory("memory")?;
+    let range = checked_range(ptr as u32, bytes.len() as u32, memory.data_size())?;
+    for (byte, cell) in bytes.iter().zip(memory.view()[range].iter()) {
+        cell.set(*byte);
+    }
+    Ok((ptr as u32, bytes.len() as u32))
+}
+
+/// Read `len` bytes out of the module's linear memory starting at `ptr`.
+fn read_bytes(instance: &Instance, ptr: u32, len: u32) -> Result<Vec<u8>> {
+    let memory = instance.exports.get_memory("memory")?;
+    let range = checked_range(ptr, len, memory.data_size())?;
+    Ok(memory.view()[range].iter().map(|cell| cell.get()).collect())
+}
+
+/// Drain whatever the guest wrote to its (in-memory, piped) stdout or
+/// stderr. Best-effort: a module that never set up a pipe for the stream
+/// simply yields no output.
+fn read_wasi_pipe(wasi_env: &mut WasiEnv, stdout: bool) -> Vec<u8> {
+    let state = wasi_env.state();
+    let mut guard = state.fs.lock().unwrap();
+    let file = if stdout {
+        guard.stdout_mut()
+    } else {
+        guard.stderr_mut()
+    };
+    let mut buf = Vec::new();
+    if let Ok(Some(file)) = file {
+        let _ = file.read_to_end(&mut buf);
+    }
+    buf
+}
+
+/// Builds a `Store` that charges one fuel point per Wasm operator executed.
+/// This (and the wall-clock watchdog in `execute_with_limits`/
+/// `call_with_limits`, which works by zeroing out the same fuel counter)
+/// only bounds time spent running Wasm bytecode: neither can do anything
+/// about time a host import spends blocked before it returns control to the
+/// guest, since the guest isn't executing any instructions to meter while
+/// that's happening. A UDF that calls a host function with its own
+/// unbounded blocking I/O (e.g. `http_get` in `host.rs`, against an
+/// unresponsive server) can still pin the calling thread indefinitely;
+/// guarding against that is each host function's own responsibility (see
+/// `HTTP_GET_TIMEOUT` in `host.rs`), not something fuel or the deadline can
+/// enforce from here.
+pub(crate) fn metered_store(fuel: u64) -> Store {
+    let metering = Arc::new(Metering::new(fuel, cost_function));
+    let mut compiler_config = Cranelift::default();
+    compiler_config.push_middleware(metering);
+    Store::new(&JIT::new(compiler_config).engine())
+}
+
+/// A host directory a WASI UDF is allowed to see, and how.
+#[derive(Clone, Debug)]
+pub struct PreopenedDir {
+    pub host_path: std::path::PathBuf,
+    /// Path the guest sees this directory mounted at. Defaults to
+    /// `host_path` itself when not given.
+    pub guest_alias: Option<String>,
+    pub writable: bool,
+}
+
+/// Capability-scoped configuration for a WASI UDF: an explicit allow-list
+/// instead of handing the guest the server's full ambient authority.
+/// `check` denies every WASI import (`wasi_snapshot_preview1` or the older
+/// `wasi_unstable`) except the
+/// always-safe baseline ([`ALWAYS_ALLOWED_IMPORTS`]) and whatever this
+/// policy grants, so the default ([`WasiPolicy::deny_all`]) really does
+/// grant nothing beyond that baseline — no environment variables, no
+/// filesystem, no clock or randomness — and operators opt capabilities in
+/// one at a time for UDFs that need them (e.g. reading a model file from a
+/// preopened, read-only directory).
+#[derive(Clone, Debug, Default)]
+pub struct WasiPolicy {
+    pub env: HashMap<String, String>,
+    pub preopened_dirs: Vec<PreopenedDir>,
+    pub allow_clock: bool,
+    pub allow_random: bool,
+}
+
+/// WASI imports every module gets regardless of policy: stdio plumbing (the
+/// pipes `wasi_env` already wires to in-memory buffers, not the host's real
+/// file descriptors), reading the args/env `wasi_env` was built with, and
+/// the two calls a well-behaved guest uses to yield or exit cleanly. None of
+/// these reach outside the sandbox `WASM::wasi_env` already constructs for
+/// the call.
+const ALWAYS_ALLOWED_IMPORTS: &[&str] = &[
+    "fd_write",
+    "fd_read",
+    "fd_close",
+    "fd_seek",
+    "fd_tell",
+    "fd_fdstat_get",
+    "fd_fdstat_set_flags",
+    "fd_prestat_get",
+    "fd_prestat_dir_name",
+    "args_get",
+    "args_sizes_get",
+    "environ_get",
+    "environ_sizes_get",
+    "proc_exit",
+    "sched_yield",
+];
+
+impl WasiPolicy {
+    /// The default, most restrictive policy.
+    pub fn deny_all() -> Self {
+        Self::default()
+    }
+
+    /// WASI imports this policy grants on top of [`ALWAYS_ALLOWED_IMPORTS`].
+    fn granted_imports(&self) -> Vec<&'static str> {
+        let mut granted = Vec::new();
+        if !self.preopened_dirs.is_empty() {
+            granted.extend_from_slice(&[
+                "path_open",
+                "path_filestat_get",
+                "fd_filestat_get",
+                "fd_readdir",
+            ]);
+            if self.preopened_dirs.iter().any(|dir| dir.writable) {
+                granted.extend_from_slice(&[
+                    "path_create_directory",
+                    "path_remove_directory",
+                    "path_unlink_file",
+                    "path_rename",
+                    "path_symlink",
+                    "fd_filestat_set_size",
+                    "fd_sync",
+                    "fd_datasync",
+                ]);
+            }
+        }
+        if self.allow_clock {
+            granted.extend_from_slice(&["clock_time_get", "clock_res_get"]);
+        }
+        if self.allow_random {
+            granted.push("random_get");
+        }
+        granted
+    }
+
+    /// Reject `module` at instantiation time if it imports a WASI syscall
+    /// that's neither always-allowed nor explicitly granted by this policy,
+    /// instead of only checking a hardcoded handful of names and letting
+    /// everything else (`sock_recv`, `path_unlink_file`, `poll_oneoff`, ...)
+    /// through unexamined.
+    fn check(&self, module: &Module) -> Result<()> {
+        let granted = self.granted_imports();
+        for import in module.imports() {
+            if import.module() != "wasi_snapshot_preview1" && import.module() != "wasi_unstable" {
+                continue;
+            }
+            let name = import.name();
+            if !ALWAYS_ALLOWED_IMPORTS.contains(&name) && !granted.contains(&name) {
+                bail!(
+                    "module imports `{}`, which this UDF's WasiPolicy doesn't grant",
+                    name
+                );
+            }
+        }
+        Ok(())
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct WASM {
     name: String,
     contents: Vec<u8>,
+    /// Pre-compiled module, populated when this `WASM` came from
+    /// `Store::get` so calls skip recompilation entirely. `None` for ad-hoc
+    /// instances (e.g. in tests), which fall back to compiling on the fly.
+    module: Option<Module>,
+    pool: Arc<Mutex<InstancePool>>,
+    wasi_policy: WasiPolicy,
 }
 
 impl WASM {
     pub fn new(name: String, contents: Vec<u8>) -> Self {
-        Self { name, contents }
+        Self {
+            name,
+            contents,
+            module: None,
+            pool: Arc::new(Mutex::new(InstancePool::default())),
+            wasi_policy: WasiPolicy::deny_all(),
+        }
+    }
+
+    /// Like `new`, but reuses an already-compiled `Module` instead of
+    /// compiling `contents` again on first use.
+    pub fn with_module(name: String, contents: Vec<u8>, module: Module) -> Self {
+        Self {
+            name,
+            contents,
+            module: Some(module),
+            pool: Arc::new(Mutex::new(InstancePool::default())),
+            wasi_policy: WasiPolicy::deny_all(),
+        }
+    }
+
+    /// Builder method: run this module's WASI imports under `policy`
+    /// instead of the default deny-all one.
+    pub fn with_wasi_policy(mut self, policy: WasiPolicy) -> Self {
+        self.wasi_policy = policy;
+        self
+    }
+
+    fn compiled_module(&self, store: &Store) -> Result<Module> {
+        match &self.module {
+            Some(module) => Ok(module.clone()),
+            None => Ok(Module::new(store, &self.contents)?),
+        }
+    }
+
+    /// Pop a warm instance off `pool`, kicking off a background refill if
+    /// this emptied it (or left it low) and nothing is refilling it
+    /// already. The refill never instantiates on the calling thread and
+    /// never holds `pool`'s mutex
+    /// across an `Instance::new`, so it doesn't serialize concurrent calls
+    /// against the same module.
+    ///
+    /// Takes `store` rather than a pre-built `ImportObject`: every
+    /// `Instance::new` needs its *own* `crate::host::import_object(store)`.
+    /// That import object's `Function`s are bound to one `HostEnv` via
+    /// `WasmerEnv`, and instantiating against the same `ImportObject` a
+    /// second time rebinds that `HostEnv`'s `LazyInit<Memory>`/
+    /// `LazyInit<NativeFunc>` fields to the new instance — so sharing one
+    /// import object across pooled instances would make a host function
+    /// called from an older instance silently read and write a newer
+    /// instance's linear memory instead of its own.
+    fn acquire_pooled(pool: &Arc<Mutex<InstancePool>>, module: &Module, store: &Store) -> Result<Instance> {
+        let popped = {
+            let mut guard = pool.lock().unwrap();
+            let popped = guard.pop_idle();
+            if guard.idle.len() < POOL_CAPACITY && !guard.refilling {
+                guard.refilling = true;
+                Self::spawn_refill(Arc::clone(pool), module.clone(), store.clone());
+            }
+            popped
+        };
+        match popped {
+            Some(instance) => Ok(instance),
+            None => {
+                let import_object = crate::host::import_object(store);
+                Instance::new(module, &import_object)
+                    .map_err(|e| anyhow!("Failed to instantiate module: {}", e))
+            }
+        }
+    }
+
+    /// Top `pool` back up to `POOL_CAPACITY` from a dedicated thread, one
+    /// instance at a time (each with its own fresh `ImportObject`, for the
+    /// reason `acquire_pooled` documents), re-checking the pool's size
+    /// under the lock between each `Instance::new` rather than holding the
+    /// lock for the whole refill.
+    fn spawn_refill(pool: Arc<Mutex<InstancePool>>, module: Module, store: Store) {
+        thread::spawn(move || {
+            loop {
+                if pool.lock().unwrap().idle.len() >= POOL_CAPACITY {
+                    break;
+                }
+                let import_object = crate::host::import_object(&store);
+                match Instance::new(&module, &import_object) {
+                    Ok(instance) => pool.lock().unwrap().idle.push(instance),
+                    Err(_) => break,
+                }
+            }
+            pool.lock().unwrap().refilling = false;
+        });
     }
 
-    pub fn execute(&self, endpoint: &str, args: Vec<String>) -> Result<Box<[Val]>> {
+    pub fn execute(&self, endpoint: &str, args: Vec<String>) -> Result<ExecutionOutput> {
         let store = Store::default();
-        let module = Module::new(&store, &self.contents)?;
-        let import_object = {
-            if self.has_wasi_imports(&module) {
-                let args = args.iter().cloned().map(|arg| arg.into_bytes());
-                let mut wasi_state_builder = WasiState::new(&self.name);
-                wasi_state_builder.args(args);
-                let mut wasi_env = wasi_state_builder.finalize()?;
-                wasi_env.import_object(&module)?
+        let module = self.compiled_module(&store)?;
+        let has_wasi_imports = self.has_wasi_imports(&module);
+        let mut wasi_env = None;
+        let instance = if has_wasi_imports {
+            // WASI args are baked into the import object per call, so a
+            // pooled instance from a previous call can't be reused here.
+            let mut env = self.wasi_env(&module, &args)?;
+            let import_object = env.import_object(&module)?;
+            let instance = Instance::new(&module, &import_object)?;
+            wasi_env = Some(env);
+            instance
+        } else {
+            Self::acquire_pooled(&self.pool, &module, &store)?
+        };
+        self.invoke_function(&instance, endpoint, &args, wasi_env.as_mut())
+    }
+
+    /// Build a `WasiEnv` for this module with stdout/stderr wired to
+    /// in-memory pipes instead of the host's actual file descriptors, so a
+    /// guest's output can be captured and returned rather than printed
+    /// straight to the server's console. Rejects `module` up front if it
+    /// imports a capability `self.wasi_policy` doesn't grant.
+    fn wasi_env(&self, module: &Module, args: &[String]) -> Result<WasiEnv> {
+        self.wasi_policy.check(module)?;
+        let wasi_args = args.iter().cloned().map(|arg| arg.into_bytes());
+        let mut wasi_state_builder = WasiState::new(&self.name);
+        wasi_state_builder
+            .args(wasi_args)
+            .stdin(Box::new(Pipe::new()))
+            .stdout(Box::new(Pipe::new()))
+            .stderr(Box::new(Pipe::new()));
+        for (key, value) in &self.wasi_policy.env {
+            wasi_state_builder.env(key, value);
+        }
+        for dir in &self.wasi_policy.preopened_dirs {
+            if dir.writable {
+                wasi_state_builder.map_dir(
+                    dir.guest_alias.as_deref().unwrap_or_else(|| {
+                        dir.host_path.to_str().unwrap_or_default()
+                    }),
+                    &dir.host_path,
+                )?;
             } else {
-                imports! {}
+                let alias = dir
+                    .guest_alias
+                    .as_deref()
+                    .unwrap_or_else(|| dir.host_path.to_str().unwrap_or_default());
+                wasi_state_builder.preopen(|p| {
+                    p.directory(&dir.host_path)
+                        .alias(alias)
+                        .read(true)
+                        .write(false)
+                })?;
             }
+        }
+        Ok(wasi_state_builder.finalize()?)
+    }
+
+    /// Like [`WASM::execute`], but bounds the guest to `fuel` instructions
+    /// and, if `deadline` is set, to that much wall-clock time. Returns the
+    /// leftover fuel alongside the result so the caller can account for the
+    /// call's cost.
+    ///
+    /// Exhausting either budget yields `Err` wrapping an [`ExecutionError`]
+    /// instead of letting the guest run unbounded.
+    pub fn execute_with_limits(
+        &self,
+        endpoint: &str,
+        args: Vec<String>,
+        fuel: u64,
+        deadline: Option<Duration>,
+    ) -> Result<MeteredResult> {
+        let store = metered_store(fuel);
+        let module = self.compiled_module(&store)?;
+        let has_wasi_imports = self.has_wasi_imports(&module);
+        let mut wasi_env = None;
+        let instance = if has_wasi_imports {
+            let mut env = self.wasi_env(&module, &args)?;
+            let import_object = env.import_object(&module)?;
+            let instance = Instance::new(&module, &import_object)?;
+            wasi_env = Some(env);
+            instance
+        } else {
+            Self::acquire_pooled(&self.pool, &module, &store)?
         };
-        let instance = Instance::new(&module, &import_object)?;
-        self.invoke_function(&instance, endpoint, &args)
+        // The module may have been compiled (and cached) against a
+        // different fuel budget than this call wants; rebase the instance's
+        // remaining points to this call's budget before running it.
+        metering::set_remaining_points(&instance, fuel);
+        let instance = Arc::new(instance);
+        let cancelled = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        let watchdog = deadline.map(|deadline| {
+            let instance = Arc::clone(&instance);
+            let cancelled = Arc::clone(&cancelled);
+            let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+            let handle = thread::spawn(move || {
+                if done_rx.recv_timeout(deadline).is_err() {
+                    // The call is still running; force the next operator
+                    // boundary to trap by draining its remaining fuel.
+                    cancelled.store(true, std::sync::atomic::Ordering::SeqCst);
+                    metering::set_remaining_points(&instance, 0);
+                }
+            });
+            (handle, done_tx)
+        });
+
+        let result = self.invoke_function(&instance, endpoint, &args, wasi_env.as_mut());
+
+        if let Some((handle, done_tx)) = watchdog {
+            let _ = done_tx.send(());
+            let _ = handle.join();
+        }
+
+        let points_remaining = match metering::get_remaining_points(&instance) {
+            MeteringPoints::Remaining(points) => points,
+            MeteringPoints::Exhausted => 0,
+        };
+
+        match result {
+            Ok(output) => Ok(MeteredResult {
+                output,
+                points_remaining,
+            }),
+            Err(err) if cancelled.load(std::sync::atomic::Ordering::SeqCst) => {
+                Err(ExecutionError::DeadlineExceeded.into())
+            }
+            Err(err) if points_remaining == 0 => Err(ExecutionError::FuelExhausted.into()),
+            Err(err) => Err(err),
+        }
     }
 
     #[inline]
@@ -47,7 +589,8 @@ impl WASM {
         instance: &Instance,
         invoke: &str,
         args: &[String],
-    ) -> Result<Box<[Val]>> {
+        wasi_env: Option<&mut WasiEnv>,
+    ) -> Result<ExecutionOutput> {
         let func: Function = self.try_find_function(&instance, invoke)?;
         let func_ty = func.ty();
         let required_arguments = func_ty.params().len();
@@ -92,18 +635,158 @@ impl WASM {
             })
             .collect::<Result<Vec<_>>>()?;
         let result = func.call(&invoke_args);
+        let (returns, exit_code) = match result {
+            Ok(returns) => (returns, None),
+            Err(err) => match err.downcast::<WasiError>() {
+                // A guest calling `proc_exit` must not be able to tear down
+                // the host process it's embedded in; report the exit code
+                // to the caller as data instead.
+                Ok(WasiError::Exit(exit_code)) => (Box::new([]) as Box<[Val]>, Some(exit_code)),
+                Ok(err) => return Err(err.into()),
+                Err(err) => return Err(err.into()),
+            },
+        };
+        let (stdout, stderr) = match wasi_env {
+            Some(wasi_env) => (
+                read_wasi_pipe(wasi_env, true),
+                read_wasi_pipe(wasi_env, false),
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+        Ok(ExecutionOutput {
+            returns,
+            stdout,
+            stderr,
+            exit_code,
+        })
+    }
+
+    /// Like [`WASM::execute_with_limits`], but for UDFs whose SQL signature
+    /// isn't just a handful of numbers: each [`WasmArg::Bytes`] is copied
+    /// into guest memory through the module's exported `udf_alloc`
+    /// allocator and passed as a `(ptr, len)` pair rather than forced
+    /// through `str::parse`. The return value is decoded the same way: a
+    /// `(i32, i32)` result is read back as bytes, a single result is handed
+    /// back as the raw scalar `Val`, and any other shape is an error.
+    pub fn call_with_limits(
+        &self,
+        endpoint: &str,
+        args: Vec<WasmArg>,
+        fuel: u64,
+        deadline: Option<Duration>,
+    ) -> Result<(TypedCallOutput, u64)> {
+        let store = metered_store(fuel);
+        let module = self.compiled_module(&store)?;
+        let has_wasi_imports = self.has_wasi_imports(&module);
+        let mut wasi_env = None;
+        let import_object = if has_wasi_imports {
+            let mut env = self.wasi_env(&module, &[])?;
+            let import_object = env.import_object(&module)?;
+            wasi_env = Some(env);
+            import_object
+        } else {
+            crate::host::import_object(&store)
+        };
+        let instance = Instance::new(&module, &import_object)?;
+        metering::set_remaining_points(&instance, fuel);
+        let instance = Arc::new(instance);
+        let cancelled = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        let watchdog = deadline.map(|deadline| {
+            let instance = Arc::clone(&instance);
+            let cancelled = Arc::clone(&cancelled);
+            let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+            let handle = thread::spawn(move || {
+                if done_rx.recv_timeout(deadline).is_err() {
+                    cancelled.store(true, std::sync::atomic::Ordering::SeqCst);
+                    metering::set_remaining_points(&instance, 0);
+                }
+            });
+            (handle, done_tx)
+        });
+
+        let result = self.invoke_typed(&instance, endpoint, args, wasi_env.as_mut());
+
+        if let Some((handle, done_tx)) = watchdog {
+            let _ = done_tx.send(());
+            let _ = handle.join();
+        }
+
+        let points_remaining = match metering::get_remaining_points(&instance) {
+            MeteringPoints::Remaining(points) => points,
+            MeteringPoints::Exhausted => 0,
+        };
+
         match result {
-            Ok(v) => Ok(v),
-            Err(err) => {
-                let err = match err.downcast::<WasiError>() {
-                    Ok(WasiError::Exit(exit_code)) => {
-                        std::process::exit(exit_code as _);
-                    }
-                    Ok(err) => err.into(),
-                    Err(err) => err.into(),
-                };
-                Err(err)
+            Ok(output) => Ok((output, points_remaining)),
+            Err(err) if cancelled.load(std::sync::atomic::Ordering::SeqCst) => {
+                Err(ExecutionError::DeadlineExceeded.into())
             }
+            Err(err) if points_remaining == 0 => Err(ExecutionError::FuelExhausted.into()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn invoke_typed(
+        &self,
+        instance: &Instance,
+        invoke: &str,
+        args: Vec<WasmArg>,
+        wasi_env: Option<&mut WasiEnv>,
+    ) -> Result<TypedCallOutput> {
+        let func = self.try_find_function(instance, invoke)?;
+        let mut wasm_args = Vec::with_capacity(args.len());
+        for arg in args {
+            match arg {
+                WasmArg::I32(v) => wasm_args.push(Val::I32(v)),
+                WasmArg::I64(v) => wasm_args.push(Val::I64(v)),
+                WasmArg::F32(v) => wasm_args.push(Val::F32(v)),
+                WasmArg::F64(v) => wasm_args.push(Val::F64(v)),
+                WasmArg::Bytes(bytes) => {
+                    let (ptr, len) = alloc_and_write(instance, &bytes)?;
+                    wasm_args.push(Val::I32(ptr as i32));
+                    wasm_args.push(Val::I32(len as i32));
+                }
+            }
+        }
+        let (value, exit_code) = match func.call(&wasm_args) {
+            Ok(results) => (Some(Self::decode_typed_result(instance, invoke, &results)?), None),
+            Err(err) => match err.downcast::<WasiError>() {
+                // Same as `invoke_function`: a guest calling `proc_exit`
+                // must be reported as a clean exit, not a failed call —
+                // there's just no return value to decode.
+                Ok(WasiError::Exit(exit_code)) => (None, Some(exit_code)),
+                Ok(err) => return Err(err.into()),
+                Err(err) => return Err(err.into()),
+            },
+        };
+        let (stdout, stderr) = match wasi_env {
+            Some(wasi_env) => (
+                read_wasi_pipe(wasi_env, true),
+                read_wasi_pipe(wasi_env, false),
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+        Ok(TypedCallOutput {
+            value,
+            stdout,
+            stderr,
+            exit_code,
+        })
+    }
+
+    fn decode_typed_result(instance: &Instance, invoke: &str, results: &[Val]) -> Result<WasmValue> {
+        match results {
+            // Convention: a function returning exactly two i32s is handing
+            // back a `(ptr, len)` pair into guest memory.
+            [Val::I32(ptr), Val::I32(len)] => {
+                Ok(WasmValue::Bytes(read_bytes(instance, *ptr as u32, *len as u32)?))
+            }
+            [scalar] => Ok(WasmValue::Scalar(scalar.clone())),
+            _ => Err(anyhow!(
+                "Don't know how to interpret the return value of `{}`",
+                invoke
+            )),
         }
     }
 
@@ -188,6 +871,63 @@ mod tests {
         wasm.execute("_start", vec!["5000000".to_owned()]).unwrap();
     }
 
+    #[test]
+    fn test_checked_range_rejects_out_of_bounds() {
+        // A guest-controlled ptr near u32::MAX must not wrap into a
+        // spuriously valid range when widened for the bounds check.
+        assert!(checked_range(u32::MAX - 1, 10, 1 << 16).is_err());
+        // In-bounds range is unaffected.
+        assert_eq!(checked_range(4, 12, 1 << 16).unwrap(), 4..16);
+        // Exactly at the end of memory is fine; one byte past is not.
+        assert!(checked_range(0, 1 << 16, 1 << 16).is_ok());
+        assert!(checked_range(1, 1 << 16, 1 << 16).is_err());
+    }
+
+    #[test]
+    fn test_wasi_policy_deny_all_grants_nothing_beyond_baseline() {
+        let policy = WasiPolicy::deny_all();
+        assert!(policy.granted_imports().is_empty());
+        // Spot-check a few of the imports a real-world malicious or buggy
+        // module might lean on; none should ever be in the baseline.
+        for name in ["path_open", "sock_recv", "clock_time_get", "random_get"] {
+            assert!(!ALWAYS_ALLOWED_IMPORTS.contains(&name));
+        }
+    }
+
+    #[test]
+    fn test_wasi_policy_grants_path_open_only_with_a_preopened_dir() {
+        let mut policy = WasiPolicy::deny_all();
+        assert!(!policy.granted_imports().contains(&"path_open"));
+
+        policy.preopened_dirs.push(PreopenedDir {
+            host_path: std::path::PathBuf::from("/tmp"),
+            guest_alias: None,
+            writable: false,
+        });
+        let granted = policy.granted_imports();
+        assert!(granted.contains(&"path_open"));
+        // Read-only preopen shouldn't imply write-capable syscalls.
+        assert!(!granted.contains(&"path_unlink_file"));
+    }
+
+    #[test]
+    fn test_wasi_policy_grants_clock_and_random_independently() {
+        let mut policy = WasiPolicy::deny_all();
+        policy.allow_clock = true;
+        assert!(policy.granted_imports().contains(&"clock_time_get"));
+        assert!(!policy.granted_imports().contains(&"random_get"));
+    }
+
+    #[test]
+    fn test_wasm_fuel_exhausted() {
+        let nbody = std::fs::read("nbody.wasm").unwrap();
+        let wasm = WASM::new("nbody".to_owned(), nbody);
+        let err = wasm
+            .execute_with_limits("_start", vec!["5000000".to_owned()], 10, None)
+            .unwrap_err();
+        assert!(err.downcast_ref::<ExecutionError>().is_some());
+    }
+
     #[test]
     fn test_http_get() {
         let wasm = std::fs::read("http.wasm").unwrap();